@@ -60,6 +60,36 @@ fn main() {
                 .long("release")
                 .help("Build artifacts in release mode, with optimizations"),
         )
+        .arg(
+            Arg::with_name("max-stack")
+                .long("max-stack")
+                .takes_value(true)
+                .value_name("ENTRY")
+                .help(
+                    "Print the worst-case stack depth reachable from ENTRY \
+                     (computed from the crate's relocatable object file)",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Output format"),
+        )
+        .arg(
+            Arg::with_name("only-global")
+                .long("only-global")
+                .conflicts_with("include-local")
+                .help("Report only global functions"),
+        )
+        .arg(
+            Arg::with_name("include-local")
+                .long("include-local")
+                .help("Report local (module-internal) functions too [default]"),
+        )
         .get_matches();
 
     match run(&matches) {
@@ -194,7 +224,24 @@ fn run(matches: &ArgMatches) -> anyhow::Result<i32> {
         }
     }
 
-    stack_sizes::run_exec(&path, &obj.expect("unreachable"))?;
+    let obj = obj.expect("unreachable");
+
+    if let Some(entry) = matches.value_of("max-stack") {
+        stack_sizes::run_max_stack(&obj, entry)?;
+    } else {
+        let format = matches
+            .value_of("format")
+            .unwrap()
+            .parse::<stack_sizes::OutputFormat>()?;
+        let filter = if matches.is_present("only-global") {
+            stack_sizes::VisibilityFilter::OnlyGlobal
+        } else {
+            // default to every defined function; `static` helpers are frequently the
+            // stack-heavy ones and were always listed before the visibility filter
+            stack_sizes::VisibilityFilter::IncludeLocal
+        };
+        stack_sizes::run_exec(&path, &obj, format, filter)?;
+    }
 
     Ok(0)
 }
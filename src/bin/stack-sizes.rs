@@ -1,6 +1,7 @@
 use std::path::Path;
 
 use clap::{App, Arg};
+use stack_sizes::{OutputFormat, VisibilityFilter};
 
 const ABOUT: &str = "Prints the stack usage of each function in an ELF file.";
 
@@ -8,6 +9,39 @@ fn main() {
     let matches = App::new("stack-sizes")
         .about(ABOUT)
         .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::with_name("max-stack")
+                .long("max-stack")
+                .takes_value(true)
+                .value_name("ENTRY")
+                .help(
+                    "Print the worst-case stack depth reachable from ENTRY \
+                     (requires a relocatable object file `.o`, not a linked ELF)",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Output format"),
+        )
+        .arg(
+            Arg::with_name("only-global")
+                .long("only-global")
+                .conflicts_with("include-local")
+                .help(
+                    "Report only global functions (linked files only; every \
+                     function of a relocatable object file is reported)",
+                ),
+        )
+        .arg(
+            Arg::with_name("include-local")
+                .long("include-local")
+                .help("Report local (module-internal) functions too [default]"),
+        )
         .arg(
             Arg::with_name("ELF")
                 .help("ELF file to analyze")
@@ -16,9 +50,29 @@ fn main() {
         )
         .get_matches();
 
-    let path = matches.value_of("ELF").unwrap();
-
-    if let Err(e) = stack_sizes::run(Path::new(path)) {
+    if let Err(e) = run(&matches) {
         eprintln!("error: {}", e);
     }
 }
+
+fn run(matches: &clap::ArgMatches<'_>) -> anyhow::Result<()> {
+    let path = Path::new(matches.value_of("ELF").unwrap());
+    let format = matches.value_of("format").unwrap().parse::<OutputFormat>()?;
+    let filter = visibility_filter(matches);
+
+    if let Some(entry) = matches.value_of("max-stack") {
+        stack_sizes::run_max_stack(path, entry)
+    } else {
+        stack_sizes::run(path, format, filter)
+    }
+}
+
+fn visibility_filter(matches: &clap::ArgMatches<'_>) -> VisibilityFilter {
+    if matches.is_present("only-global") {
+        VisibilityFilter::OnlyGlobal
+    } else {
+        // default to every defined function; `static` helpers are frequently the
+        // stack-heavy ones and were always listed before the visibility filter
+        VisibilityFilter::IncludeLocal
+    }
+}
@@ -6,7 +6,6 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-use core::u16;
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
     io::Cursor,
@@ -14,13 +13,11 @@ use std::{
 #[cfg(feature = "tools")]
 use std::{fs, path::Path};
 
-use anyhow::{anyhow, bail};
+use anyhow::bail;
 use byteorder::{ReadBytesExt, LE};
-use xmas_elf::{
-    header,
-    sections::SectionData,
-    symbol_table::{Entry, Type},
-    ElfFile,
+use object::{
+    read::{File, Object, ObjectSection, ObjectSymbol},
+    ObjectKind, RelocationTarget, SectionKind, SymbolKind,
 };
 
 /// Functions found after analyzing an executable
@@ -36,12 +33,41 @@ pub struct Functions<'a> {
     pub defined: BTreeMap<u64, Function<'a>>,
 }
 
+/// The linker visibility (binding) of a function symbol
+///
+/// Ordered from least to most visible, so the most visible binding of a set of
+/// aliased symbols can be picked with [`Ord`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Visibility {
+    /// A module-internal symbol (`STB_LOCAL`)
+    Local,
+
+    /// A weakly-bound symbol (`STB_WEAK`)
+    Weak,
+
+    /// An exported symbol (`STB_GLOBAL`)
+    Global,
+}
+
+impl Visibility {
+    fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Local => "local",
+            Visibility::Weak => "weak",
+            Visibility::Global => "global",
+        }
+    }
+}
+
 /// A symbol that represents a function (subroutine)
 #[derive(Clone, Debug)]
 pub struct Function<'a> {
     names: Vec<&'a str>,
     size: u64,
     stack: Option<u64>,
+    visibility: Visibility,
+    location: Option<(String, u32)>,
+    inlined: Vec<String>,
 }
 
 impl<'a> Function<'a> {
@@ -59,6 +85,222 @@ impl<'a> Function<'a> {
     pub fn stack(&self) -> Option<u64> {
         self.stack
     }
+
+    /// Returns the linker visibility of the function
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    /// Returns the source file and line this function was defined at
+    ///
+    /// This requires the analyzed binary to carry DWARF debug information; it's
+    /// `None` otherwise.
+    pub fn location(&self) -> Option<(&str, u32)> {
+        self.location
+            .as_ref()
+            .map(|(file, line)| (file.as_str(), *line))
+    }
+
+    /// Returns the (demangled) names of the inlined subroutines that contribute to
+    /// this function's frame, innermost first
+    pub fn inlined(&self) -> &[String] {
+        &self.inlined
+    }
+}
+
+/// The worst-case stack depth reachable from some entry point
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackEstimate<'a> {
+    /// A concrete worst case was computed
+    Exact {
+        /// The worst-case stack usage in bytes
+        stack: u64,
+
+        /// The chain of functions (entry point first) that produces `stack`
+        path: Vec<&'a str>,
+    },
+
+    /// The call graph reachable from the entry point contains recursion, so the
+    /// stack depth cannot be bounded by this analysis
+    Unbounded,
+
+    /// The reachable call graph could not be fully resolved (a callee is missing
+    /// `.stack_sizes` information, a relocation points at a non-function symbol, or
+    /// a function's address is taken, e.g. for an indirect call); `stack` is a lower
+    /// bound obtained from the part of the graph that *could* be resolved
+    Incomplete {
+        /// A lower bound on the worst-case stack usage in bytes
+        stack: u64,
+
+        /// The chain of functions (entry point first) that produces `stack`
+        path: Vec<&'a str>,
+    },
+
+    /// The entry point was not found in the call graph
+    Unknown,
+}
+
+/// The call graph of a relocatable object file, used to estimate the whole-program
+/// worst-case stack depth reachable from an entry point
+///
+/// An edge `caller -> callee` is emitted for every call relocation (a direct call
+/// or jump) whose offset falls inside `caller`'s `[value, value + size)` range and
+/// whose target symbol is a function; any other relocation inside `caller` marks
+/// its estimate as incomplete instead. See [`build_call_graph`].
+#[derive(Clone, Debug)]
+pub struct CallGraph<'a> {
+    edges: BTreeMap<&'a str, HashSet<&'a str>>,
+    stack: HashMap<&'a str, Option<u64>>,
+    // functions whose outgoing edges could not be fully resolved (a relocation
+    // inside them points at a non-function symbol)
+    incomplete: HashSet<&'a str>,
+}
+
+impl<'a> CallGraph<'a> {
+    /// Returns the outgoing edges (`caller -> callees`) of the call graph
+    pub fn edges(&self) -> &BTreeMap<&'a str, HashSet<&'a str>> {
+        &self.edges
+    }
+
+    /// Estimates the worst-case stack usage reachable from the `entry` function,
+    /// including all of its transitive callees
+    pub fn max_stack(&self, entry: &str) -> StackEstimate<'a> {
+        let entry = match self.key(entry) {
+            Some(entry) => entry,
+            None => return StackEstimate::Unknown,
+        };
+
+        // recursion would make the memoized DFS below loop forever, so rule it out first
+        if self.is_recursive(entry) {
+            return StackEstimate::Unbounded;
+        }
+
+        let mut memo = HashMap::new();
+        let (stack, path, complete) = self.depth(entry, &mut memo);
+        if complete {
+            StackEstimate::Exact { stack, path }
+        } else {
+            StackEstimate::Incomplete { stack, path }
+        }
+    }
+
+    // resolves a caller-supplied name to the matching key in the graph
+    fn key(&self, name: &str) -> Option<&'a str> {
+        self.stack
+            .keys()
+            .chain(self.edges.keys())
+            .find(|key| **key == name)
+            .copied()
+    }
+
+    // memoized DFS over the (acyclic) reachable sub-graph; returns the worst-case
+    // stack usage, the path that produced it, and whether it is fully resolved
+    fn depth(
+        &self,
+        f: &'a str,
+        memo: &mut HashMap<&'a str, (u64, Vec<&'a str>, bool)>,
+    ) -> (u64, Vec<&'a str>, bool) {
+        if let Some(cached) = memo.get(f) {
+            return cached.clone();
+        }
+
+        let own = self.stack.get(f).copied().flatten();
+        let mut complete = own.is_some() && !self.incomplete.contains(f);
+        let own = own.unwrap_or(0);
+
+        let mut worst = 0;
+        let mut worst_path = vec![];
+        if let Some(callees) = self.edges.get(f) {
+            for &callee in callees {
+                let (stack, path, callee_complete) = self.depth(callee, memo);
+                complete &= callee_complete;
+                if stack > worst {
+                    worst = stack;
+                    worst_path = path;
+                }
+            }
+        }
+
+        let mut path = vec![f];
+        path.extend(worst_path);
+        let result = (own + worst, path, complete);
+        memo.insert(f, result.clone());
+        result
+    }
+
+    // Tarjan's strongly-connected-components algorithm over the sub-graph reachable
+    // from `entry`; any SCC with more than one node, or a node with a self-edge,
+    // denotes recursion
+    fn is_recursive(&self, entry: &'a str) -> bool {
+        let mut index = HashMap::new();
+        let mut low = HashMap::new();
+        let mut on_stack = HashSet::new();
+        let mut stack = vec![];
+        let mut counter = 0;
+        let mut recursive = false;
+        self.scc(
+            entry,
+            &mut index,
+            &mut low,
+            &mut on_stack,
+            &mut stack,
+            &mut counter,
+            &mut recursive,
+        );
+        recursive
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn scc(
+        &self,
+        v: &'a str,
+        index: &mut HashMap<&'a str, usize>,
+        low: &mut HashMap<&'a str, usize>,
+        on_stack: &mut HashSet<&'a str>,
+        stack: &mut Vec<&'a str>,
+        counter: &mut usize,
+        recursive: &mut bool,
+    ) {
+        index.insert(v, *counter);
+        low.insert(v, *counter);
+        *counter += 1;
+        stack.push(v);
+        on_stack.insert(v);
+
+        if let Some(callees) = self.edges.get(v) {
+            for &w in callees {
+                if w == v {
+                    *recursive = true;
+                }
+
+                if !index.contains_key(w) {
+                    self.scc(w, index, low, on_stack, stack, counter, recursive);
+                    let lw = low[w];
+                    let entry = low.get_mut(v).unwrap();
+                    *entry = (*entry).min(lw);
+                } else if on_stack.contains(w) {
+                    let iw = index[w];
+                    let entry = low.get_mut(v).unwrap();
+                    *entry = (*entry).min(iw);
+                }
+            }
+        }
+
+        if low[v] == index[v] {
+            let mut size = 0;
+            loop {
+                let w = stack.pop().unwrap();
+                on_stack.remove(w);
+                size += 1;
+                if w == v {
+                    break;
+                }
+            }
+            if size > 1 {
+                *recursive = true;
+            }
+        }
+    }
 }
 
 // is this symbol a tag used to delimit code / data sections within a subroutine?
@@ -69,191 +311,329 @@ fn is_tag(name: &str) -> bool {
     }
 }
 
-fn process_symtab_obj<'a, E>(
-    entries: &'a [E],
-    elf: &ElfFile<'a>,
-) -> anyhow::Result<
-    (
-        BTreeMap<u16, BTreeMap<u64, HashSet<&'a str>>>,
-        BTreeMap<u32, u16>,
-    )
->
-where
-    E: Entry,
-{
-    let mut names: BTreeMap<_, BTreeMap<_, HashSet<_>>> = BTreeMap::new();
-    let mut shndxs = BTreeMap::new();
-
-    for (entry, i) in entries.iter().zip(0..) {
-        let name = entry.get_name(elf);
-        let shndx = entry.shndx();
-        let addr = entry.value() & !1; // clear the thumb bit
-        let ty = entry.get_type();
-
-        if shndx != 0 {
-            shndxs.insert(i, shndx);
-        }
-
-        if ty == Ok(Type::Func)
-            || (ty == Ok(Type::NoType)
-                && name
-                    .map(|name| !name.is_empty() && !is_tag(name))
-                    .unwrap_or(false))
-        {
-            let name = name.map_err(anyhow::Error::msg)?;
+// is this a linker-generated or section-relative label rather than a real alias?
+// these collapse onto a function's address but aren't names worth reporting
+fn is_internal_label(name: &str) -> bool {
+    is_tag(name) || name.starts_with("..") || name.starts_with('$') || name.starts_with('@')
+}
 
-            names
-                .entry(shndx)
-                .or_default()
-                .entry(addr)
-                .or_default()
-                .insert(name);
-        }
+// LLVM names the stack-size metadata section `.stack_sizes` on ELF and PE/COFF
+// but `__stack_sizes` on Mach-O; accept either so the extraction is genuinely
+// format-agnostic
+fn is_stack_sizes_section(name: &str) -> bool {
+    name == ".stack_sizes" || name == "__stack_sizes"
+}
+
+// does this relocation encode a direct call/jump, as opposed to an address-taken
+// function pointer materialized by a data relocation (e.g. `R_ARM_ABS32` /
+// `R_X86_64_64` in a literal pool)? only the former is a real call-graph edge
+//
+// `object` normalizes PC-relative and PLT-relative relocations on some targets,
+// but leaves ARM/AArch64 branch relocations as raw `Elf(r_type)` values, so those
+// have to be matched per architecture or every `bl`/`blx` on Cortex-M is missed
+fn is_call_relocation(arch: object::Architecture, kind: object::RelocationKind) -> bool {
+    use object::{Architecture, RelocationKind};
+
+    if matches!(kind, RelocationKind::Relative | RelocationKind::PltRelative) {
+        return true;
     }
 
-    Ok((names, shndxs))
+    match (arch, kind) {
+        // R_ARM_PC24, R_ARM_THM_CALL, R_ARM_PLT32, R_ARM_CALL, R_ARM_JUMP24, R_ARM_THM_JUMP24
+        (Architecture::Arm, RelocationKind::Elf(ty)) => matches!(ty, 1 | 10 | 27 | 28 | 29 | 30),
+        // R_AARCH64_JUMP26, R_AARCH64_CALL26
+        (Architecture::Aarch64, RelocationKind::Elf(ty)) => matches!(ty, 282 | 283),
+        _ => false,
+    }
 }
 
 /// Parses an *input* (AKA relocatable) object file (`.o`) and returns a list of symbols and their
 /// stack usage
+///
+/// The input format (ELF, Mach-O, PE/COFF, wasm, ...) is auto-detected from its header.
 pub fn analyze_object(obj: &[u8]) -> anyhow::Result<HashMap<&str, u64>> {
-    let elf = &ElfFile::new(obj).map_err(anyhow::Error::msg)?;
+    let file = File::parse(obj).map_err(anyhow::Error::msg)?;
 
-    if elf.header.pt2.type_().as_type() != header::Type::Relocatable {
+    if file.kind() != ObjectKind::Relocatable {
         bail!("object file is not relocatable")
     }
 
-    // shndx -> (address -> [symbol-name])
-    let mut is_64_bit = false;
-    let (shndx2names, symtab2shndx) = match elf
-        .find_section_by_name(".symtab")
-        .ok_or_else(|| anyhow!("`.symtab` section not found"))?
-        .get_data(elf)
-    {
-        Ok(SectionData::SymbolTable32(entries)) => process_symtab_obj(entries, elf)?,
+    let is_64_bit = file.is_64();
 
-        Ok(SectionData::SymbolTable64(entries)) => {
-            is_64_bit = true;
-            process_symtab_obj(entries, elf)?
+    let mut sizes = HashMap::new();
+    for section in file.sections() {
+        if !section.name().map(is_stack_sizes_section).unwrap_or(false) {
+            continue;
         }
 
-        _ => bail!("malformed .symtab section"),
-    };
+        let data = section.data().map_err(anyhow::Error::msg)?;
 
-    let mut sizes = HashMap::new();
-    let mut sections = elf.section_iter();
-    while let Some(section) = sections.next() {
-        if section.get_name(elf) == Ok(".stack_sizes") {
-            let mut stack_sizes = Cursor::new(section.raw_data(elf));
-
-            // next section should be `.rel.stack_sizes` or `.rela.stack_sizes`
-            // XXX should we check the section name?
-            let relocs: Vec<_> = match sections
-                .next()
-                .and_then(|section| section.get_data(elf).ok())
+        // each entry's address field is fixed up by a relocation pointing at the
+        // function it describes; pair them up by the relocation offset
+        let mut relocs: Vec<(u64, object::SymbolIndex)> = section
+            .relocations()
+            .filter_map(|(offset, reloc)| match reloc.target() {
+                RelocationTarget::Symbol(index) => Some((offset, index)),
+                _ => None,
+            })
+            .collect();
+        relocs.sort_unstable_by_key(|&(offset, _)| offset);
+
+        let mut cursor = Cursor::new(data);
+        for (offset, index) in relocs {
+            cursor.set_position(offset);
+
+            // skip past the (relocated) address field
+            if is_64_bit {
+                cursor.read_u64::<LE>()?;
+            } else {
+                cursor.read_u32::<LE>()?;
+            }
+            let stack = leb128::read::unsigned(&mut cursor)?;
+
+            let name = file
+                .symbol_by_index(index)
+                .map_err(anyhow::Error::msg)?
+                .name()
+                .map_err(anyhow::Error::msg)?;
+
+            sizes.insert(name, stack);
+        }
+    }
+
+    Ok(sizes)
+}
+
+/// Builds the [`CallGraph`] of a relocatable object file (`.o`) from the relocations
+/// covering its executable sections and its `.stack_sizes` data
+///
+/// Works across every format the [`object`] crate understands (ELF, Mach-O, PE/COFF,
+/// wasm, ...), auto-detecting the format from the file header.
+pub fn build_call_graph(obj: &[u8]) -> anyhow::Result<CallGraph<'_>> {
+    let file = File::parse(obj).map_err(anyhow::Error::msg)?;
+
+    if file.kind() != ObjectKind::Relocatable {
+        bail!(
+            "`--max-stack` requires a relocatable object file (`.o`); a linked \
+             executable no longer carries the relocations the call graph is built from"
+        )
+    }
+
+    let is_64_bit = file.is_64();
+    let arch = file.architecture();
+
+    // section index -> (address -> canonical function name)
+    let mut canon: BTreeMap<usize, BTreeMap<u64, &str>> = BTreeMap::new();
+    // section index -> sorted [(start, end, canonical name)]
+    let mut funcs: BTreeMap<usize, Vec<(u64, u64, &str)>> = BTreeMap::new();
+
+    for symbol in file.symbols() {
+        if symbol.kind() != SymbolKind::Text || symbol.is_undefined() {
+            continue;
+        }
+
+        let name = match symbol.name() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        if let object::SymbolSection::Section(index) = symbol.section() {
+            let value = symbol.address() & !1; // clear the thumb bit
+            let names = canon.entry(index.0).or_default();
+            let canonical = *names.entry(value).or_insert(name);
+            // record the range once, under the canonical (first seen) name
+            if canonical == name {
+                funcs
+                    .entry(index.0)
+                    .or_default()
+                    .push((value, value + symbol.size(), canonical));
+            }
+        }
+    }
+
+    for ranges in funcs.values_mut() {
+        ranges.sort_unstable_by_key(|&(start, ..)| start);
+    }
+
+    let mut edges: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+    let mut stack: HashMap<&str, Option<u64>> = HashMap::new();
+    for names in canon.values() {
+        for &name in names.values() {
+            edges.entry(name).or_default();
+            stack.entry(name).or_insert(None);
+        }
+    }
+    let mut incomplete = HashSet::new();
+
+    for section in file.sections() {
+        if section.kind() != SectionKind::Text {
+            continue;
+        }
+
+        let ranges = match funcs.get(&section.index().0) {
+            Some(ranges) => ranges,
+            None => continue,
+        };
+
+        for (offset, reloc) in section.relocations() {
+            let caller = match ranges
+                .iter()
+                .find(|&&(start, end, _)| offset >= start && offset < end)
             {
-                Some(SectionData::Rel32(rels)) if !is_64_bit => rels
-                    .iter()
-                    .map(|rel| rel.get_symbol_table_index())
-                    .collect(),
-
-                Some(SectionData::Rela32(relas)) if !is_64_bit => relas
-                    .iter()
-                    .map(|rel| rel.get_symbol_table_index())
-                    .collect(),
-
-                Some(SectionData::Rel64(rels)) if is_64_bit => rels
-                    .iter()
-                    .map(|rel| rel.get_symbol_table_index())
-                    .collect(),
-
-                Some(SectionData::Rela64(relas)) if is_64_bit => relas
-                    .iter()
-                    .map(|rel| rel.get_symbol_table_index())
-                    .collect(),
-
-                _ => bail!("expected a section with relocation information after `.stack_sizes`"),
+                Some(&(_, _, name)) => name,
+                None => continue,
             };
 
-            for index in relocs {
-                let addr = if is_64_bit {
-                    stack_sizes.read_u64::<LE>()?
-                } else {
-                    u64::from(stack_sizes.read_u32::<LE>()?)
-                };
-                let stack = leb128::read::unsigned(&mut stack_sizes).unwrap();
-
-                let shndx = symtab2shndx[&index];
-                let entries = shndx2names
-                    .get(&(shndx as u16))
-                    .unwrap_or_else(|| panic!("section header with index {} not found", shndx));
-
-                assert!(sizes
-                    .insert(
-                        *entries
-                            .get(&addr)
-                            .unwrap_or_else(|| panic!(
-                                "symbol with address {} not found at section {} ({:?})",
-                                addr, shndx, entries
-                            ))
-                            .iter()
-                            .next()
-                            .unwrap(),
-                        stack
-                    )
-                    .is_none());
+            let index = match reloc.target() {
+                RelocationTarget::Symbol(index) => index,
+                _ => continue,
+            };
+
+            // a *call* relocation landing on a function is a call-graph edge; one
+            // landing on a non-function symbol means we can't enumerate the callees,
+            // so the caller's estimate is incomplete
+            match file.symbol_by_index(index) {
+                Ok(symbol) if symbol.kind() == SymbolKind::Text => {
+                    // a non-call relocation pointing at a function takes its address
+                    // (an indirect call we can't resolve), so the caller's callee set
+                    // is incomplete rather than a plain edge
+                    if !is_call_relocation(arch, reloc.kind()) {
+                        incomplete.insert(caller);
+                        continue;
+                    }
+
+                    // a defined callee folds onto its canonical name; an undefined
+                    // (external) callee keeps its own name and, lacking a
+                    // `.stack_sizes` entry, renders the estimate incomplete anyway
+                    let callee = symbol
+                        .name()
+                        .ok()
+                        .map(|name| match symbol.section() {
+                            object::SymbolSection::Section(sindex) => canon
+                                .get(&sindex.0)
+                                .and_then(|m| m.get(&(symbol.address() & !1)))
+                                .copied()
+                                .unwrap_or(name),
+                            _ => name,
+                        });
+
+                    if let Some(callee) = callee {
+                        edges.get_mut(caller).unwrap().insert(callee);
+                    } else {
+                        incomplete.insert(caller);
+                    }
+                }
+
+                _ => {
+                    incomplete.insert(caller);
+                }
             }
+        }
+    }
 
-            if stack_sizes.position() != stack_sizes.get_ref().len() as u64 {
-                bail!(
-                    "the number of relocations doesn't match the number of `.stack_sizes` entries"
-                );
+    // fold in the per-function stack usage
+    for section in file.sections() {
+        if !section.name().map(is_stack_sizes_section).unwrap_or(false) {
+            continue;
+        }
+
+        let data = section.data().map_err(anyhow::Error::msg)?;
+        let mut relocs: Vec<(u64, object::SymbolIndex)> = section
+            .relocations()
+            .filter_map(|(offset, reloc)| match reloc.target() {
+                RelocationTarget::Symbol(index) => Some((offset, index)),
+                _ => None,
+            })
+            .collect();
+        relocs.sort_unstable_by_key(|&(offset, _)| offset);
+
+        let mut cursor = Cursor::new(data);
+        for (offset, index) in relocs {
+            cursor.set_position(offset);
+
+            if is_64_bit {
+                cursor.read_u64::<LE>()?;
+            } else {
+                cursor.read_u32::<LE>()?;
+            }
+            let usage = leb128::read::unsigned(&mut cursor)?;
+
+            if let Ok(symbol) = file.symbol_by_index(index) {
+                if let object::SymbolSection::Section(sindex) = symbol.section() {
+                    if let Some(name) = canon
+                        .get(&sindex.0)
+                        .and_then(|m| m.get(&(symbol.address() & !1)))
+                        .copied()
+                    {
+                        stack.insert(name, Some(usage));
+                    }
+                }
             }
         }
     }
 
-    Ok(sizes)
+    Ok(CallGraph {
+        edges,
+        stack,
+        incomplete,
+    })
 }
 
-fn process_symtab_exec<'a, E>(
-    entries: &'a [E],
-    elf: &ElfFile<'a>,
-) -> anyhow::Result<(HashSet<&'a str>, BTreeMap<u64, Function<'a>>)>
-where
-    E: Entry + core::fmt::Debug,
-{
-    let mut defined = BTreeMap::new();
-    let mut maybe_aliases = BTreeMap::new();
-    let mut undefined = HashSet::new();
+/// Parses an executable file and returns a list of functions and their stack usage
+///
+/// The input format (ELF, Mach-O, PE/COFF, wasm, ...) is auto-detected from its header.
+pub fn analyze_executable(bytes: &[u8]) -> anyhow::Result<Functions<'_>> {
+    let file = File::parse(bytes).map_err(anyhow::Error::msg)?;
 
-    for entry in entries {
-        let ty = entry.get_type();
-        let value = entry.value();
-        let size = entry.size();
-        let name = entry.get_name(&elf);
+    let have_32_bit_addresses = !file.is_64();
 
-        if ty == Ok(Type::Func) {
-            let name = name.map_err(anyhow::Error::msg)?;
+    let mut defined: BTreeMap<u64, Function<'_>> = BTreeMap::new();
+    let mut maybe_aliases: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+    let mut undefined = HashSet::new();
 
-            if value == 0 && size == 0 {
-                undefined.insert(name);
-            } else {
-                defined
-                    .entry(value)
-                    .or_insert(Function {
+    for symbol in file.symbols() {
+        let name = match symbol.name() {
+            Ok(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+
+        match symbol.kind() {
+            SymbolKind::Text => {
+                if symbol.is_undefined() {
+                    undefined.insert(name);
+                } else {
+                    let visibility = if symbol.is_weak() {
+                        Visibility::Weak
+                    } else if symbol.scope() == object::SymbolScope::Compilation {
+                        Visibility::Local
+                    } else {
+                        Visibility::Global
+                    };
+
+                    let func = defined.entry(symbol.address()).or_insert(Function {
                         names: vec![],
-                        size,
+                        size: symbol.size(),
                         stack: None,
-                    })
-                    .names
-                    .push(name);
+                        visibility: Visibility::Local,
+                        location: None,
+                        inlined: vec![],
+                    });
+                    func.names.push(name);
+                    // aliased symbols can disagree; keep the most visible binding
+                    func.visibility = func.visibility.max(visibility);
+                }
             }
-        } else if ty == Ok(Type::NoType) {
-            if let Ok(name) = name {
-                if !is_tag(name) {
-                    maybe_aliases.entry(value).or_insert(vec![]).push(name);
+
+            // local labels can alias a function's address (e.g. the thumb bit set
+            // vs. clear); collect the real ones and fold them in below, dropping
+            // linker-generated and section-relative labels
+            SymbolKind::Label | SymbolKind::Unknown => {
+                if !is_internal_label(name) {
+                    maybe_aliases.entry(symbol.address()).or_default().push(name);
                 }
             }
+
+            _ => {}
         }
     }
 
@@ -266,31 +646,11 @@ where
         }
     }
 
-    Ok((undefined, defined))
-}
-
-/// Parses an executable ELF file and returns a list of functions and their stack usage
-pub fn analyze_executable(elf: &[u8]) -> anyhow::Result<Functions<'_>> {
-    let elf = &ElfFile::new(elf).map_err(anyhow::Error::msg)?;
-
-    let mut have_32_bit_addresses = false;
-    let (undefined, mut defined) = if let Some(section) = elf.find_section_by_name(".symtab") {
-        match section.get_data(elf).map_err(anyhow::Error::msg)? {
-            SectionData::SymbolTable32(entries) => {
-                have_32_bit_addresses = true;
-
-                process_symtab_exec(entries, elf)?
-            }
-
-            SectionData::SymbolTable64(entries) => process_symtab_exec(entries, elf)?,
-            _ => bail!("malformed .symtab section"),
-        }
-    } else {
-        (HashSet::new(), BTreeMap::new())
-    };
-
-    if let Some(stack_sizes) = elf.find_section_by_name(".stack_sizes") {
-        let data = stack_sizes.raw_data(elf);
+    let stack_sizes = file
+        .sections()
+        .find(|section| section.name().map(is_stack_sizes_section).unwrap_or(false));
+    if let Some(section) = stack_sizes {
+        let data = section.data().map_err(anyhow::Error::msg)?;
         let end = data.len() as u64;
         let mut cursor = Cursor::new(data);
 
@@ -307,8 +667,38 @@ pub fn analyze_executable(elf: &[u8]) -> anyhow::Result<Functions<'_>> {
                 sym.stack = Some(stack);
             } else if let Some(sym) = defined.get_mut(&(address & !1)) {
                 sym.stack = Some(stack);
-            } else {
-                unreachable!()
+            }
+        }
+    }
+
+    // attribute each function to its source location via DWARF, when present;
+    // binaries without debug info simply leave these fields empty
+    if let Ok(ctx) = addr2line::Context::new(&file) {
+        for (address, func) in defined.iter_mut() {
+            let probe = address & !1; // clear the thumb bit
+
+            if let Ok(Some(location)) = ctx.find_location(probe) {
+                if let Some(file) = location.file {
+                    func.location = Some((file.to_owned(), location.line.unwrap_or(0)));
+                }
+            }
+
+            if let Ok(mut frames) = ctx.find_frames(probe) {
+                let mut names = vec![];
+                while let Ok(Some(frame)) = frames.next() {
+                    if let Some(function) = frame.function {
+                        if let Ok(name) = function.demangle() {
+                            names.push(name.into_owned());
+                        }
+                    }
+                }
+
+                // `find_frames` yields the inlined subroutines innermost first and
+                // the function itself last; keep only the inlined ones
+                if names.len() > 1 {
+                    names.truncate(names.len() - 1);
+                    func.inlined = names;
+                }
             }
         }
     }
@@ -320,54 +710,243 @@ pub fn analyze_executable(elf: &[u8]) -> anyhow::Result<Functions<'_>> {
     })
 }
 
+/// How the command-line front-ends render the analysis
+#[cfg(feature = "tools")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Tab-separated human-readable table (the default)
+    Text,
+
+    /// A stable JSON array of per-function records, suitable for CI budgeting and
+    /// other tooling
+    Json,
+}
+
+/// Which functions the command-line front-ends report, by visibility
+#[cfg(feature = "tools")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisibilityFilter {
+    /// Exported functions only (global and weak)
+    Exported,
+
+    /// Global functions only
+    OnlyGlobal,
+
+    /// Every function, including module-internal (local) ones; the default, as
+    /// `static` helpers are often the stack-hungry ones worth reporting
+    IncludeLocal,
+}
+
+#[cfg(feature = "tools")]
+impl VisibilityFilter {
+    fn accepts(self, visibility: Visibility) -> bool {
+        match self {
+            VisibilityFilter::Exported => visibility >= Visibility::Weak,
+            VisibilityFilter::OnlyGlobal => visibility == Visibility::Global,
+            VisibilityFilter::IncludeLocal => true,
+        }
+    }
+}
+
+#[cfg(feature = "tools")]
+impl core::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => bail!("unknown output format `{}` (expected `text` or `json`)", s),
+        }
+    }
+}
+
+// a single function's entry in the rendered report
+#[cfg(feature = "tools")]
+#[derive(serde::Serialize)]
+struct Report<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<u64>,
+    name: String,
+    mangled: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    aliases: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_stack: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visibility: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    inlined: Vec<String>,
+}
+
+// the whole-program worst case for a function, as a plain number for the report;
+// `Incomplete` is surfaced as its lower bound, and the report does not mark it
+// apart from an exact worst case; `Unbounded` and `Unknown` yield `None`
+#[cfg(feature = "tools")]
+fn max_stack(call_graph: &CallGraph<'_>, name: &str) -> Option<u64> {
+    match call_graph.max_stack(name) {
+        StackEstimate::Exact { stack, .. } | StackEstimate::Incomplete { stack, .. } => Some(stack),
+        StackEstimate::Unbounded | StackEstimate::Unknown => None,
+    }
+}
+
+// funnels the object-, 32-bit-exec- and 64-bit-exec code paths through a single
+// emitter so the two formats stay in sync
+#[cfg(feature = "tools")]
+fn emit(
+    format: OutputFormat,
+    have_32_bit_addresses: bool,
+    reports: &[Report<'_>],
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(reports)?);
+        }
+
+        OutputFormat::Text => {
+            let with_address = reports.iter().any(|report| report.address.is_some());
+            // only show the location column when at least one function carries it
+            let with_location = reports.iter().any(|report| report.file.is_some());
+
+            // renders the trailing `file:line` column, when present
+            let location = |report: &Report<'_>| match (&report.file, report.line) {
+                (Some(file), Some(line)) if with_location => format!("\t{}:{}", file, line),
+                (Some(file), None) if with_location => format!("\t{}", file),
+                _ if with_location => "\t?".to_string(),
+                _ => String::new(),
+            };
+
+            if with_address {
+                if have_32_bit_addresses {
+                    print!("address\t\tstack\tname");
+                } else {
+                    print!("address\t\t\tstack\tname");
+                }
+                println!("{}", if with_location { "\tlocation" } else { "" });
+
+                for report in reports {
+                    if let (Some(address), Some(stack)) = (report.address, report.stack) {
+                        if have_32_bit_addresses {
+                            print!("{:#010x}\t{}\t{}", address, stack, report.name);
+                        } else {
+                            print!("{:#018x}\t{}\t{}", address, stack, report.name);
+                        }
+                        println!("{}", location(report));
+                    }
+                }
+            } else {
+                println!("stack\tname");
+
+                for report in reports {
+                    if let Some(stack) = report.stack {
+                        println!("{}\t{}", stack, report.name);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "tools")]
 #[doc(hidden)]
-pub fn run_exec(exec: &Path, obj: &Path) -> anyhow::Result<()> {
+pub fn run_exec(
+    exec: &Path,
+    obj: &Path,
+    format: OutputFormat,
+    filter: VisibilityFilter,
+) -> anyhow::Result<()> {
     let exec = &fs::read(exec)?;
     let obj = &fs::read(obj)?;
 
     let stack_sizes = analyze_object(obj)?;
     let symbols = analyze_executable(exec)?;
+    let call_graph = build_call_graph(obj)?;
 
-    if symbols.have_32_bit_addresses {
-        // 32-bit address space
-        println!("address\t\tstack\tname");
+    let mut reports = vec![];
+    for (addr, sym) in &symbols.defined {
+        if !filter.accepts(sym.visibility()) {
+            continue;
+        }
 
-        for (addr, sym) in symbols.defined {
-            let stack = sym
-                .names()
-                .iter()
-                .filter_map(|name| stack_sizes.get(name))
-                .next();
-
-            if let (Some(name), Some(stack)) = (sym.names().first(), stack) {
-                println!(
-                    "{:#010x}\t{}\t{}",
-                    addr,
-                    stack,
-                    rustc_demangle::demangle(name)
-                );
-            }
+        let name = match sym.names().first() {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let stack = sym
+            .names()
+            .iter()
+            .filter_map(|name| stack_sizes.get(name))
+            .next()
+            .copied();
+
+        // the worst case is keyed on whichever alias the call graph knows
+        let max_stack = sym.names().iter().find_map(|name| max_stack(&call_graph, name));
+
+        reports.push(Report {
+            address: Some(*addr),
+            name: rustc_demangle::demangle(name).to_string(),
+            mangled: name,
+            aliases: sym.names()[1..].to_vec(),
+            size: Some(sym.size()),
+            stack,
+            max_stack,
+            visibility: Some(sym.visibility().as_str()),
+            file: sym.location().map(|(file, _)| file.to_owned()),
+            line: sym.location().map(|(_, line)| line),
+            inlined: sym.inlined().to_vec(),
+        });
+    }
+
+    emit(format, symbols.have_32_bit_addresses, &reports)
+}
+
+/// Computes and prints the worst-case stack depth reachable from `entry`
+///
+/// `path` must point at a relocatable object file (`.o`): the call graph is
+/// reconstructed from its relocations, which a linked executable no longer
+/// carries. `cargo-stack-sizes` feeds the per-crate object here; for the
+/// `stack-sizes` front-end, pass an object file rather than the final image.
+#[cfg(feature = "tools")]
+#[doc(hidden)]
+pub fn run_max_stack(path: &Path, entry: &str) -> anyhow::Result<()> {
+    let bytes = &fs::read(path)?;
+    let call_graph = build_call_graph(bytes)?;
+
+    let demangled = |path: &[&str]| {
+        path.iter()
+            .map(|name| rustc_demangle::demangle(name).to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    };
+
+    match call_graph.max_stack(entry) {
+        StackEstimate::Exact { stack, path } => {
+            println!("max-stack\t{}", stack);
+            println!("path\t\t{}", demangled(&path));
         }
-    } else {
-        // 64-bit address space
-        println!("address\t\t\tstack\tname");
 
-        for (addr, sym) in symbols.defined {
-            let stack = sym
-                .names()
-                .iter()
-                .filter_map(|name| stack_sizes.get(name))
-                .next();
-
-            if let (Some(name), Some(stack)) = (sym.names().first(), stack) {
-                println!(
-                    "{:#018x}\t{}\t{}",
-                    addr,
-                    stack,
-                    rustc_demangle::demangle(name)
-                );
-            }
+        StackEstimate::Incomplete { stack, path } => {
+            println!("max-stack\t>= {} (incomplete)", stack);
+            println!("path\t\t{}", demangled(&path));
+        }
+
+        StackEstimate::Unbounded => {
+            bail!("the call graph reachable from `{}` is recursive", entry)
+        }
+
+        StackEstimate::Unknown => {
+            bail!("`{}` was not found in the call graph", entry)
         }
     }
 
@@ -376,23 +955,39 @@ pub fn run_exec(exec: &Path, obj: &Path) -> anyhow::Result<()> {
 
 #[cfg(feature = "tools")]
 #[doc(hidden)]
-pub fn run(path: &Path) -> anyhow::Result<()> {
+pub fn run(path: &Path, format: OutputFormat, filter: VisibilityFilter) -> anyhow::Result<()> {
     let bytes = &fs::read(path)?;
-    let elf = &ElfFile::new(bytes).map_err(anyhow::Error::msg)?;
+    let file = File::parse(bytes.as_slice()).map_err(anyhow::Error::msg)?;
 
-    if elf.header.pt2.type_().as_type() == header::Type::Relocatable {
+    if file.kind() == ObjectKind::Relocatable {
         let symbols = analyze_object(bytes)?;
 
         if symbols.is_empty() {
             bail!("this object file contains no stack usage information");
         }
 
-        println!("stack\tname");
-        for (name, stack) in symbols {
-            println!("{}\t{}", stack, rustc_demangle::demangle(name));
-        }
-
-        Ok(())
+        let call_graph = build_call_graph(bytes)?;
+
+        let mut reports = symbols
+            .into_iter()
+            .map(|(name, stack)| Report {
+                address: None,
+                name: rustc_demangle::demangle(name).to_string(),
+                mangled: name,
+                aliases: vec![],
+                size: None,
+                stack: Some(stack),
+                max_stack: max_stack(&call_graph, name),
+                visibility: None,
+                file: None,
+                line: None,
+                inlined: vec![],
+            })
+            .collect::<Vec<_>>();
+        // the object symbols come out of a `HashMap`; sort for a stable report
+        reports.sort_unstable_by(|a, b| a.mangled.cmp(b.mangled));
+
+        emit(format, false, &reports)
     } else {
         let symbols = analyze_executable(bytes)?;
 
@@ -404,36 +999,145 @@ pub fn run(path: &Path) -> anyhow::Result<()> {
             bail!("this executable contains no stack usage information");
         }
 
-        if symbols.have_32_bit_addresses {
-            // 32-bit address space
-            println!("address\t\tstack\tname");
+        let mut reports = vec![];
+        for (addr, sym) in &symbols.defined {
+            if !filter.accepts(sym.visibility()) {
+                continue;
+            }
+
+            let name = match sym.names().first() {
+                Some(name) => name,
+                None => continue,
+            };
 
-            for (addr, sym) in symbols.defined {
-                if let (Some(name), Some(stack)) = (sym.names().first(), sym.stack()) {
-                    println!(
-                        "{:#010x}\t{}\t{}",
-                        addr,
-                        stack,
-                        rustc_demangle::demangle(name)
-                    );
-                }
+            reports.push(Report {
+                address: Some(*addr),
+                name: rustc_demangle::demangle(name).to_string(),
+                mangled: name,
+                aliases: sym.names()[1..].to_vec(),
+                size: Some(sym.size()),
+                stack: sym.stack(),
+                max_stack: None,
+                visibility: Some(sym.visibility().as_str()),
+                file: sym.location().map(|(file, _)| file.to_owned()),
+                line: sym.location().map(|(_, line)| line),
+                inlined: sym.inlined().to_vec(),
+            });
+        }
+
+        emit(format, symbols.have_32_bit_addresses, &reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges<'a>(pairs: &[(&'a str, &[&'a str])]) -> BTreeMap<&'a str, HashSet<&'a str>> {
+        pairs
+            .iter()
+            .map(|(caller, callees)| (*caller, callees.iter().copied().collect()))
+            .collect()
+    }
+
+    fn stacks<'a>(pairs: &[(&'a str, Option<u64>)]) -> HashMap<&'a str, Option<u64>> {
+        pairs.iter().copied().collect()
+    }
+
+    #[test]
+    fn call_relocations_are_classified_per_architecture() {
+        use object::{Architecture, RelocationKind};
+
+        // kinds `object` normalizes across targets
+        assert!(is_call_relocation(
+            Architecture::X86_64,
+            RelocationKind::PltRelative
+        ));
+        assert!(!is_call_relocation(
+            Architecture::X86_64,
+            RelocationKind::Absolute
+        ));
+
+        // ARM `bl`/`blx` reach us as raw ELF types, not `Relative`/`PltRelative`
+        assert!(is_call_relocation(
+            Architecture::Arm,
+            RelocationKind::Elf(28) // R_ARM_CALL
+        ));
+        assert!(is_call_relocation(
+            Architecture::Arm,
+            RelocationKind::Elf(10) // R_ARM_THM_CALL
+        ));
+        // an address-taken `R_ARM_ABS32` is not a call edge
+        assert!(!is_call_relocation(
+            Architecture::Arm,
+            RelocationKind::Elf(2) // R_ARM_ABS32
+        ));
+
+        // AArch64 `bl`
+        assert!(is_call_relocation(
+            Architecture::Aarch64,
+            RelocationKind::Elf(283) // R_AARCH64_CALL26
+        ));
+    }
+
+    #[test]
+    fn max_stack_reports_exact_worst_case_path() {
+        let call_graph = CallGraph {
+            edges: edges(&[("main", &["foo", "bar"]), ("foo", &["bar"]), ("bar", &[])]),
+            stack: stacks(&[("main", Some(8)), ("foo", Some(16)), ("bar", Some(4))]),
+            incomplete: HashSet::new(),
+        };
+
+        assert_eq!(
+            call_graph.max_stack("main"),
+            StackEstimate::Exact {
+                stack: 28,
+                path: vec!["main", "foo", "bar"],
             }
-        } else {
-            // 64-bit address space
-            println!("address\t\t\tstack\tname");
-
-            for (addr, sym) in symbols.defined {
-                if let (Some(name), Some(stack)) = (sym.names().first(), sym.stack()) {
-                    println!(
-                        "{:#018x}\t{}\t{}",
-                        addr,
-                        stack,
-                        rustc_demangle::demangle(name)
-                    );
-                }
+        );
+    }
+
+    #[test]
+    fn max_stack_flags_recursion_as_unbounded() {
+        let call_graph = CallGraph {
+            edges: edges(&[("a", &["b"]), ("b", &["a"])]),
+            stack: stacks(&[("a", Some(8)), ("b", Some(8))]),
+            incomplete: HashSet::new(),
+        };
+
+        assert_eq!(call_graph.max_stack("a"), StackEstimate::Unbounded);
+    }
+
+    #[test]
+    fn max_stack_is_incomplete_when_a_callee_is_unresolved() {
+        let call_graph = CallGraph {
+            edges: edges(&[("main", &["helper"]), ("helper", &[])]),
+            stack: stacks(&[("main", Some(8)), ("helper", Some(16))]),
+            incomplete: ["helper"].iter().copied().collect(),
+        };
+
+        assert_eq!(
+            call_graph.max_stack("helper"),
+            StackEstimate::Incomplete {
+                stack: 16,
+                path: vec!["helper"],
             }
-        }
+        );
+        // the incompleteness propagates up to the caller
+        assert!(matches!(
+            call_graph.max_stack("main"),
+            StackEstimate::Incomplete { .. }
+        ));
+    }
+
+    #[test]
+    fn max_stack_of_unknown_entry_is_unknown() {
+        let call_graph = CallGraph {
+            edges: BTreeMap::new(),
+            stack: HashMap::new(),
+            incomplete: HashSet::new(),
+        };
 
-        Ok(())
+        assert_eq!(call_graph.max_stack("nope"), StackEstimate::Unknown);
     }
 }